@@ -0,0 +1,472 @@
+// Minimal client for the ADB wire protocol, talking directly to the local
+// adb server over TCP/IP instead of shelling out to `adb pull` or `gvfs-copy`.
+//
+// Only what is needed to pull a single file through the `sync:` service is
+// implemented: connect, select a transport, switch to sync mode, and drive
+// the RECV command until DONE/FAIL.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+const ADB_SERVER_ADDR: &str = "127.0.0.1:5037";
+// the sync protocol streams file contents in chunks no larger than this.
+const MAX_SYNC_CHUNK: u32 = 64 * 1024;
+// st_mode bits, as reported by STAT/DENT and expected by SEND.
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+
+// a single entry returned by a sync LIST request.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub mode: u32,
+    pub size: u32,
+    pub mtime: u32,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+// result of a sync STAT request: file mode, size in bytes, and mtime as a
+// unix timestamp, as reported by the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub mode: u32,
+    pub size: u32,
+    pub mtime: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncCommand {
+    Data,
+    Dent,
+    Done,
+    Fail,
+    List,
+    Okay,
+    Quit,
+    Recv,
+    Send,
+    Stat,
+}
+
+impl SyncCommand {
+    fn as_bytes(self) -> &'static [u8; 4] {
+        match self {
+            SyncCommand::Data => b"DATA",
+            SyncCommand::Dent => b"DENT",
+            SyncCommand::Done => b"DONE",
+            SyncCommand::Fail => b"FAIL",
+            SyncCommand::List => b"LIST",
+            SyncCommand::Okay => b"OKAY",
+            SyncCommand::Quit => b"QUIT",
+            SyncCommand::Recv => b"RECV",
+            SyncCommand::Send => b"SEND",
+            SyncCommand::Stat => b"STAT",
+        }
+    }
+
+    fn from_bytes(bytes: &[u8; 4]) -> io::Result<SyncCommand> {
+        match bytes {
+            b"DATA" => Ok(SyncCommand::Data),
+            b"DENT" => Ok(SyncCommand::Dent),
+            b"DONE" => Ok(SyncCommand::Done),
+            b"FAIL" => Ok(SyncCommand::Fail),
+            b"LIST" => Ok(SyncCommand::List),
+            b"OKAY" => Ok(SyncCommand::Okay),
+            b"QUIT" => Ok(SyncCommand::Quit),
+            b"RECV" => Ok(SyncCommand::Recv),
+            b"SEND" => Ok(SyncCommand::Send),
+            b"STAT" => Ok(SyncCommand::Stat),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown sync command: {:?}", other),
+            )),
+        }
+    }
+}
+
+// an open connection to the adb server, already switched into the `sync:`
+// service for a single selected device transport.
+pub struct SyncConnection {
+    stream: TcpStream,
+}
+
+// the helpers below are generic over Read/Write, rather than pinned to
+// TcpStream, so the wire-framing logic can be exercised in tests against an
+// in-memory std::io::Cursor instead of a live adb server.
+
+fn read_exact_bytes<R: Read>(stream: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    stream.read_exact(buf)
+}
+
+fn read_u32_le<R: Read>(stream: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    read_exact_bytes(stream, &mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+// adb host-side requests are length-prefixed with a 4 ASCII hex digit length,
+// e.g. the 18-byte message "host:transport-any" is sent as "0012host:transport-any".
+fn send_host_request<W: Write>(stream: &mut W, payload: &str) -> io::Result<()> {
+    let header = format!("{:04x}", payload.len());
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(payload.as_bytes())?;
+    Ok(())
+}
+
+fn read_status<R: Read>(stream: &mut R) -> io::Result<()> {
+    let mut status = [0u8; 4];
+    read_exact_bytes(stream, &mut status)?;
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => {
+            let len = read_hex_length(stream)?;
+            let mut message = vec![0u8; len];
+            read_exact_bytes(stream, &mut message)?;
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("adb server: {}", String::from_utf8_lossy(&message)),
+            ))
+        }
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected adb status: {:?}", other),
+        )),
+    }
+}
+
+// the sync:-level OKAY/FAIL acknowledgement following a SEND, as seen at the
+// end of push_file. Unlike the host-level read_status, the FAIL message
+// length here is a 4-byte little-endian integer rather than 4 ASCII hex
+// digits, matching every other length in the sync service's binary framing
+// (DATA/DENT/STAT all use read_u32_le too).
+fn read_sync_status<R: Read>(stream: &mut R) -> io::Result<()> {
+    let mut status = [0u8; 4];
+    read_exact_bytes(stream, &mut status)?;
+    match &status {
+        // OKAY is still followed by a 4-byte length (always 0) to keep the
+        // framing uniform with FAIL; it must be consumed here so the
+        // connection is left positioned at the next reply rather than with
+        // that length sitting unread on the stream.
+        b"OKAY" => {
+            read_u32_le(stream)?;
+            Ok(())
+        }
+        b"FAIL" => {
+            let len = read_u32_le(stream)? as usize;
+            let mut message = vec![0u8; len];
+            read_exact_bytes(stream, &mut message)?;
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("sync SEND failed: {}", String::from_utf8_lossy(&message)),
+            ))
+        }
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected sync status: {:?}", other),
+        )),
+    }
+}
+
+fn read_hex_length<R: Read>(stream: &mut R) -> io::Result<usize> {
+    let mut buf = [0u8; 4];
+    read_exact_bytes(stream, &mut buf)?;
+    let text = std::str::from_utf8(&buf)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    usize::from_str_radix(text, 16).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl SyncConnection {
+    // connect to the local adb server and select a device transport. `serial`
+    // selects a specific device; pass `None` to fall back to the only
+    // connected device (`host:transport-any`), which is all this tool needs
+    // since it only ever talks to one phone at a time.
+    pub fn connect(serial: Option<&str>) -> io::Result<SyncConnection> {
+        let mut stream = TcpStream::connect(ADB_SERVER_ADDR)?;
+
+        let transport_request = match serial {
+            Some(serial) => format!("host:transport:{}", serial),
+            None => "host:transport-any".to_string(),
+        };
+        send_host_request(&mut stream, &transport_request)?;
+        read_status(&mut stream)?;
+
+        send_host_request(&mut stream, "sync:")?;
+        read_status(&mut stream)?;
+
+        Ok(SyncConnection { stream })
+    }
+
+    // pull a single file from the device at `remote_path` and write its
+    // contents to `local_path`, creating/truncating it as needed.
+    pub fn pull_file(&mut self, remote_path: &str, local_path: &Path) -> io::Result<()> {
+        let mut dest_file = fs::File::create(local_path)?;
+        self.pull_to(remote_path, &mut dest_file)
+    }
+
+    // pull a single file from the device at `remote_path`, writing its
+    // contents to an arbitrary writer rather than a local file path.
+    pub fn pull_to(&mut self, remote_path: &str, writer: &mut dyn Write) -> io::Result<()> {
+        self.send_sync_request(SyncCommand::Recv, remote_path.as_bytes())?;
+
+        loop {
+            let mut header = [0u8; 4];
+            read_exact_bytes(&mut self.stream, &mut header)?;
+            match SyncCommand::from_bytes(&header)? {
+                SyncCommand::Data => {
+                    let chunk_len = read_u32_le(&mut self.stream)?;
+                    if chunk_len > MAX_SYNC_CHUNK {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("sync DATA chunk too large: {} bytes", chunk_len),
+                        ));
+                    }
+                    let mut chunk = vec![0u8; chunk_len as usize];
+                    read_exact_bytes(&mut self.stream, &mut chunk)?;
+                    writer.write_all(&chunk)?;
+                }
+                SyncCommand::Done => {
+                    // 4-byte mtime follows; the caller compares size/mtime
+                    // separately via STAT, so it is read and discarded here.
+                    let _mtime = read_u32_le(&mut self.stream)?;
+                    return Ok(());
+                }
+                SyncCommand::Fail => {
+                    let len = read_u32_le(&mut self.stream)? as usize;
+                    let mut message = vec![0u8; len];
+                    read_exact_bytes(&mut self.stream, &mut message)?;
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("sync RECV failed: {}", String::from_utf8_lossy(&message)),
+                    ));
+                }
+                other => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected sync reply: {:?}", other),
+                    ));
+                }
+            }
+        }
+    }
+
+    // query mode/size/mtime for a file on the device, used to decide whether
+    // it needs (re-)transferring before spending time on a full pull.
+    pub fn stat_file(&mut self, remote_path: &str) -> io::Result<FileStat> {
+        self.send_sync_request(SyncCommand::Stat, remote_path.as_bytes())?;
+
+        let mut header = [0u8; 4];
+        read_exact_bytes(&mut self.stream, &mut header)?;
+        match SyncCommand::from_bytes(&header)? {
+            SyncCommand::Stat => {
+                let mode = read_u32_le(&mut self.stream)?;
+                let size = read_u32_le(&mut self.stream)?;
+                let mtime = read_u32_le(&mut self.stream)?;
+                Ok(FileStat { mode, size, mtime })
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected reply to STAT: {:?}", other),
+            )),
+        }
+    }
+
+    // list the entries of a directory on the device, skipping `.`/`..`.
+    pub fn list_dir(&mut self, remote_dir: &str) -> io::Result<Vec<DirEntry>> {
+        self.send_sync_request(SyncCommand::List, remote_dir.as_bytes())?;
+
+        let mut entries = Vec::new();
+        loop {
+            let mut header = [0u8; 4];
+            read_exact_bytes(&mut self.stream, &mut header)?;
+            match SyncCommand::from_bytes(&header)? {
+                SyncCommand::Dent => {
+                    let mode = read_u32_le(&mut self.stream)?;
+                    let size = read_u32_le(&mut self.stream)?;
+                    let mtime = read_u32_le(&mut self.stream)?;
+                    let name_len = read_u32_le(&mut self.stream)? as usize;
+                    let mut name_bytes = vec![0u8; name_len];
+                    read_exact_bytes(&mut self.stream, &mut name_bytes)?;
+                    let name = String::from_utf8_lossy(&name_bytes).into_owned();
+                    if name != "." && name != ".." {
+                        entries.push(DirEntry { name, mode, size, mtime });
+                    }
+                }
+                SyncCommand::Done => {
+                    // a LIST reply's DONE is followed by 16 unused bytes.
+                    let mut trailer = [0u8; 16];
+                    read_exact_bytes(&mut self.stream, &mut trailer)?;
+                    return Ok(entries);
+                }
+                other => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected reply to LIST: {:?}", other),
+                    ));
+                }
+            }
+        }
+    }
+
+    // push `reader`'s contents to the device at `remote_path` with the given
+    // mode and mtime, mirroring `pull_file` in reverse.
+    pub fn push_file(&mut self, reader: &mut dyn Read, remote_path: &str, mode: u32, mtime: u32) -> io::Result<()> {
+        let send_request = format!("{},{}", remote_path, mode);
+        self.send_sync_request(SyncCommand::Send, send_request.as_bytes())?;
+
+        let mut buffer = [0u8; MAX_SYNC_CHUNK as usize];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.stream.write_all(SyncCommand::Data.as_bytes())?;
+            self.stream.write_all(&(read as u32).to_le_bytes())?;
+            self.stream.write_all(&buffer[..read])?;
+        }
+
+        self.stream.write_all(SyncCommand::Done.as_bytes())?;
+        self.stream.write_all(&mtime.to_le_bytes())?;
+        read_sync_status(&mut self.stream)
+    }
+
+    fn send_sync_request(&mut self, command: SyncCommand, path: &[u8]) -> io::Result<()> {
+        self.stream.write_all(command.as_bytes())?;
+        self.stream.write_all(&(path.len() as u32).to_le_bytes())?;
+        self.stream.write_all(path)?;
+        Ok(())
+    }
+}
+
+// convenience wrapper for the common case: pull one file using whichever
+// device is currently connected.
+pub fn pull_file(remote_path: &str, local_path: &Path) -> io::Result<()> {
+    let mut connection = SyncConnection::connect(None)?;
+    connection.pull_file(remote_path, local_path)
+}
+
+// convenience wrapper for the common case: stat one file using whichever
+// device is currently connected.
+pub fn stat_file(remote_path: &str) -> io::Result<FileStat> {
+    let mut connection = SyncConnection::connect(None)?;
+    connection.stat_file(remote_path)
+}
+
+// convenience wrapper for the common case: list one directory using whichever
+// device is currently connected.
+pub fn list_dir(remote_dir: &str) -> io::Result<Vec<DirEntry>> {
+    let mut connection = SyncConnection::connect(None)?;
+    connection.list_dir(remote_dir)
+}
+
+// convenience wrapper for the common case: push one file using whichever
+// device is currently connected.
+pub fn push_file(reader: &mut dyn Read, remote_path: &str, mode: u32, mtime: u32) -> io::Result<()> {
+    let mut connection = SyncConnection::connect(None)?;
+    connection.push_file(reader, remote_path, mode, mtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sync_command_round_trips_through_bytes() {
+        let commands = [
+            SyncCommand::Data,
+            SyncCommand::Dent,
+            SyncCommand::Done,
+            SyncCommand::Fail,
+            SyncCommand::List,
+            SyncCommand::Okay,
+            SyncCommand::Quit,
+            SyncCommand::Recv,
+            SyncCommand::Send,
+            SyncCommand::Stat,
+        ];
+        for command in commands {
+            assert_eq!(SyncCommand::from_bytes(command.as_bytes()).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn sync_command_from_bytes_rejects_unknown() {
+        assert!(SyncCommand::from_bytes(b"NOPE").is_err());
+    }
+
+    #[test]
+    fn dir_entry_is_dir_checks_the_format_bits() {
+        let dir = DirEntry { name: "a".into(), mode: S_IFDIR | 0o755, size: 0, mtime: 0 };
+        let file = DirEntry { name: "b".into(), mode: 0o100644, size: 0, mtime: 0 };
+        assert!(dir.is_dir());
+        assert!(!file.is_dir());
+    }
+
+    #[test]
+    fn send_host_request_uses_four_digit_hex_length_prefix() {
+        let mut buf = Vec::new();
+        send_host_request(&mut buf, "host:transport-any").unwrap();
+        assert_eq!(buf, b"0012host:transport-any");
+    }
+
+    #[test]
+    fn read_status_accepts_okay() {
+        let mut cursor = Cursor::new(b"OKAY".to_vec());
+        assert!(read_status(&mut cursor).is_ok());
+    }
+
+    #[test]
+    fn read_status_parses_hex_framed_fail_message() {
+        // "no devices" is 10 bytes, framed as 4 ASCII hex digits per the
+        // host-level protocol.
+        let mut cursor = Cursor::new(b"FAIL000ano devices".to_vec());
+        let err = read_status(&mut cursor).unwrap_err();
+        assert!(err.to_string().contains("no devices"));
+    }
+
+    #[test]
+    fn read_sync_status_consumes_okays_trailing_length() {
+        // OKAY is followed by a 4-byte length (always 0); a byte left over
+        // after this call would mean the next read on a reused connection
+        // misparses that leftover length as the start of the next reply.
+        let mut payload = b"OKAY".to_vec();
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        payload.extend_from_slice(b"DONE");
+        let mut cursor = Cursor::new(payload);
+        assert!(read_sync_status(&mut cursor).is_ok());
+        let mut next_reply = [0u8; 4];
+        cursor.read_exact(&mut next_reply).unwrap();
+        assert_eq!(&next_reply, b"DONE");
+    }
+
+    #[test]
+    fn read_sync_status_parses_binary_framed_fail_message() {
+        // the sync:-level framing uses a 4-byte little-endian length instead
+        // of the host-level's ASCII hex digits.
+        let mut payload = b"FAIL".to_vec();
+        payload.extend_from_slice(&10u32.to_le_bytes());
+        payload.extend_from_slice(b"no devices");
+        let mut cursor = Cursor::new(payload);
+        let err = read_sync_status(&mut cursor).unwrap_err();
+        assert!(err.to_string().contains("no devices"));
+    }
+
+    #[test]
+    fn read_hex_length_parses_four_ascii_hex_digits() {
+        let mut cursor = Cursor::new(b"001a".to_vec());
+        assert_eq!(read_hex_length(&mut cursor).unwrap(), 0x1a);
+    }
+
+    #[test]
+    fn read_u32_le_reads_little_endian() {
+        let mut cursor = Cursor::new(42u32.to_le_bytes().to_vec());
+        assert_eq!(read_u32_le(&mut cursor).unwrap(), 42);
+    }
+}
@@ -1,40 +1,80 @@
 #[macro_use]
 extern crate log;
 extern crate chrono;
+extern crate ftp;
+extern crate libc;
 extern crate log4rs;
+extern crate udev;
+extern crate walkdir;
 
-use std::collections::BTreeSet;
+mod adb;
+mod backend;
+mod usb;
+
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
+use std::io;
 use std::io::{BufRead, BufReader, Result, Write};
 use std::path::Path;
-use std::process::Command;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use chrono::Local;
 
+use backend::{Fileinfo, StorageBackend};
+
 
-// template for the final source directory location where to search files from.
-// the __BUS__ and __DEVICE__ will be replaced in the string once finding the
-// correct device using the ``lsusb`` command, and searching the output using
-// DEVICE_NAME.
-const SOURCE_DIR_TEMPLATE: &str = "/run/user/1000/gvfs/mtp:host=%5Busb%3A__BUS__%2C__DEVICE__%5D/Phone/DCIM/Camera";
+// root of the phone's own storage, as seen by the adb sync service running
+// on the device -- not a gvfs mount point. the whole tree under this root
+// is walked recursively, so this is no longer pinned to a single folder
+// like DCIM/Camera.
+const SOURCE_STORAGE_ROOT: &str = "/sdcard";
 const DESTINATION_DIR: &str = "/home/hannu/move/files/to/path";
-const DEVICE_NAME: &str = "Samsung";
 // file to log entries in for successful transfers. logs date and number of files transferred
 const LOG_FILE: &str = "/home/hannu/move/files/to/path/successful_transfers.log";
 // file to keep list of transferred file names
 const TRANSFERRED_FILES_FILE: &str = "/home/hannu/move/files/to/path/transferred_files.txt";
-const WAIT_TIME_CONNECT_LOOP: u64 = 5;
-const WAIT_TIME_DISCONNECT_LOOP: u64 = 5;
+// how many files to copy at once, to avoid overwhelming the machine with
+// hundreds of simultaneous transfers.
+const MAX_CONCURRENT_TRANSFERS: usize = 4;
+// how often to log aggregate throughput/ETA while a transfer is in progress.
+const PROGRESS_LOG_INTERVAL_SECS: u64 = 5;
+// how long to wait before retrying adb_sync_reachable when a device is
+// present but adbd isn't answering yet.
+const ADB_SYNC_RETRY_INTERVAL_SECS: u64 = 5;
 
 
 fn path_exists(path: &str) -> bool {
     Path::new(path).exists()
 }
 
-fn load_transferred_files(transferred_files_file: &str) -> Result<BTreeSet<String>> {
-    // Load previously transferred list of files, represented in a BTreeSet.
+// a previously transferred file's destination path plus the source size/mtime
+// it was transferred at, so a later edit or re-shot file reusing the same
+// name is detected as needing a re-transfer instead of being skipped forever.
+struct TransferRecord {
+    path: String,
+    size: u64,
+    mtime: u64,
+}
+
+impl TransferRecord {
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}", self.path, self.size, self.mtime)
+    }
+
+    fn from_line(line: &str) -> Option<TransferRecord> {
+        let mut fields = line.splitn(3, '\t');
+        let path = fields.next()?.to_string();
+        let size: u64 = fields.next()?.parse().ok()?;
+        let mtime: u64 = fields.next()?.parse().ok()?;
+        Some(TransferRecord { path, size, mtime })
+    }
+}
+
+fn load_transferred_files(transferred_files_file: &str) -> Result<BTreeMap<String, TransferRecord>> {
+    // Load previously transferred file records, keyed by destination path.
     // If the file does not exist, it is created.
     debug!("Loading transferred files...");
     let file = fs::OpenOptions::new()
@@ -43,11 +83,14 @@ fn load_transferred_files(transferred_files_file: &str) -> Result<BTreeSet<Strin
         .create(true)
         .open(transferred_files_file)
         .unwrap();
-    let mut file_list: BTreeSet<String> = BTreeSet::new();
+    let mut records: BTreeMap<String, TransferRecord> = BTreeMap::new();
     for line in BufReader::new(file).lines() {
-        file_list.insert(line.unwrap());
+        let line = line.unwrap();
+        if let Some(record) = TransferRecord::from_line(&line) {
+            records.insert(record.path.clone(), record);
+        }
     }
-    Ok(file_list)
+    Ok(records)
 }
 
 fn append_lines_to_file(file_path: &str, lines: &[&str]) -> Result<()> {
@@ -66,13 +109,11 @@ fn append_lines_to_file(file_path: &str, lines: &[&str]) -> Result<()> {
     Ok(())
 }
 
-fn save_transferred_files(transferred_files_file: &str, transferred_files: &BTreeSet<String>) -> Result<()> {
-    // Append new transferred files into the existing file that keeps track of transferred files.
+fn save_transferred_files(transferred_files_file: &str, transferred_files: &BTreeMap<String, TransferRecord>) -> Result<()> {
+    // Append new transferred file records into the existing file that keeps track of transferred files.
     debug!("Updating list of transferred files...");
-    let mut lines: Vec<&str> = Vec::new();
-    for file_path in transferred_files {
-        lines.push(file_path);
-    }
+    let lines: Vec<String> = transferred_files.values().map(TransferRecord::to_line).collect();
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
     append_lines_to_file(transferred_files_file, &lines)?;
     Ok(())
 }
@@ -90,16 +131,138 @@ fn log_action(log_file: &str, transferred_files_count: &i32) -> Result<()> {
     Ok(())
 }
 
-fn transfer_files(source_dir: &str, destination_dir: &str) -> Result<()> {
-    // Copy files from source_dir to destination_dir. Save destination file paths
-    // of copied files to a file transferred_files.txt, in the directory destination_dir.
-    // Files that have been once previously transferred, will not get transferred again.
+// a Write wrapper that counts bytes as they pass through, so a single large
+// file's progress shows up in the aggregate throughput even before it finishes.
+struct ProgressWriter<'a, W: Write> {
+    inner: W,
+    bytes_written: &'a AtomicU64,
+}
+
+impl<'a, W: Write> Write for ProgressWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes_written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+// how many chunks a pipe_transfer bridge will buffer between the reading and
+// writing side before the writer blocks, so a slow destination backpressures
+// the source instead of the whole file piling up in memory.
+const PIPE_CHANNEL_DEPTH: usize = 4;
+
+// the writing half of an in-process pipe between a backend's `get` and the
+// other backend's `put`, so a file is streamed chunk-by-chunk instead of
+// being fully buffered in memory first.
+struct ChannelWriter {
+    sender: mpsc::SyncSender<Vec<u8>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "destination closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// the reading half of the same pipe; reassembles the chunks `ChannelWriter`
+// sent, in order, and reports EOF once the writer side is dropped.
+struct ChannelReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    leftover: Vec<u8>,
+    position: usize,
+}
+
+impl io::Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.position >= self.leftover.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.leftover = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let available = &self.leftover[self.position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+// stream `path` from `source` straight into `destination` at `destination_path`,
+// without ever holding the whole file in memory: `source.get` and
+// `destination.put` run concurrently on either end of a bounded channel.
+fn pipe_transfer(
+    source: &(dyn StorageBackend + Sync),
+    source_path: &str,
+    destination: &(dyn StorageBackend + Sync),
+    destination_path: &str,
+    bytes_written: &AtomicU64,
+) -> backend::BackendResult<()> {
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(PIPE_CHANNEL_DEPTH);
+    let mut reader = ChannelReader { receiver, leftover: Vec::new(), position: 0 };
+
+    thread::scope(|scope| {
+        let get_handle = scope.spawn(|| {
+            let mut writer = ChannelWriter { sender };
+            let mut progress_writer = ProgressWriter { inner: &mut writer, bytes_written };
+            source.get(source_path, &mut progress_writer)
+        });
+
+        let put_result = destination.put(destination_path, &mut reader);
+        drop(reader);
+        let get_result = get_handle.join().unwrap();
+
+        // a failing put drops the reader, which breaks the pipe and makes the
+        // get side fail too; report the put's error in that case since it's
+        // the actual root cause.
+        put_result.and(get_result)
+    })
+}
+
+fn log_progress(files_done: usize, files_total: usize, bytes_done: u64, bytes_total: u64, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64().max(0.001);
+    let throughput = bytes_done as f64 / seconds;
+    let eta_seconds = if throughput > 0.0 {
+        (bytes_total.saturating_sub(bytes_done)) as f64 / throughput
+    } else {
+        0.0
+    };
+    info!(
+        "{}/{} files, {:.1} MiB/s, ETA {:.0}s",
+        files_done, files_total, throughput / (1024.0 * 1024.0), eta_seconds
+    );
+}
+
+fn transfer_files(
+    source: &(dyn StorageBackend + Sync),
+    destination: &(dyn StorageBackend + Sync),
+    source_dir: &str,
+    destination_dir: &str,
+) -> Result<()> {
+    // Copy files from source_dir to destination_dir on their respective backends,
+    // using up to MAX_CONCURRENT_TRANSFERS workers at once. Save destination file
+    // paths and the source size/mtime they were copied at to transferred_files.txt,
+    // in the directory destination_dir. A file is skipped only if its size and
+    // mtime still match the record from its last transfer; otherwise it is
+    // re-copied, which also catches files that were only partially copied last time.
 
     info!("Begin syncing files from source: {} to destination: {}...", source_dir, destination_dir);
 
     let previously_transferred_files = load_transferred_files(TRANSFERRED_FILES_FILE)?;
-    let mut new_transferred_files: BTreeSet<String> = BTreeSet::new();
-    let mut count_files_transferred = 0;
 
     if ! path_exists(destination_dir) {
         info!("Creating destination directory: {}...", destination_dir);
@@ -108,44 +271,79 @@ fn transfer_files(source_dir: &str, destination_dir: &str) -> Result<()> {
 
     info!("Beginning transfer...");
 
-    for dir_entry_result in fs::read_dir(source_dir)? {
-        let entry = dir_entry_result.unwrap();
-        let destination_file_path = format!("{}/{}", destination_dir, entry.file_name().into_string().unwrap());
+    // walk recursively rather than a single `read_dir`, so files nested in
+    // subfolders are found and their relative path is preserved at the
+    // destination instead of everything being flattened into one directory.
+    let entries = source.walk(source_dir).map_err(io::Error::from)?;
 
-        if previously_transferred_files.contains(&destination_file_path) {
-            // files that have already been transferred once, even if manually removed
-            // from the destination directory, should never be transferred again.
-            continue;
-        }
+    let pending: VecDeque<Fileinfo> = entries
+        .into_iter()
+        .filter(|entry| {
+            let destination_file_path = format!("{}/{}", destination_dir, entry.name);
+            match previously_transferred_files.get(&destination_file_path) {
+                // same size and mtime as the last successful transfer: nothing changed.
+                Some(record) => !(record.size == entry.metadata.size && record.mtime == entry.metadata.mtime),
+                None => true,
+            }
+        })
+        .collect();
 
-        let source_file_path = entry.path().into_os_string().into_string().unwrap();
+    let files_total = pending.len();
+    let bytes_total: u64 = pending.iter().map(|entry| entry.metadata.size).sum();
 
-        // copy from MTP (Media Transfer Protocol) file system requires a bit
-        // more special method to copy files from...
-        Command::new("gvfs-copy")
-            .arg(&source_file_path)
-            .arg(&destination_file_path)
-            .spawn()
-            .expect("Failed to copy file");
+    let work_queue = Mutex::new(pending);
+    let new_transferred_files: Mutex<BTreeMap<String, TransferRecord>> = Mutex::new(BTreeMap::new());
+    let bytes_done = AtomicU64::new(0);
+    let files_done = AtomicUsize::new(0);
+    let files_remaining = AtomicUsize::new(files_total);
+    let started_at = Instant::now();
 
-        // the above command only seems to initiate the transfer, and then return immediately,
-        // even if using .spawn().unwrap().wait()... so best to sleep manually between
-        // each transfer start, to give each file transfer some time to proceed and not
-        // clog down the entire machine with potentially hundreads of simultaenous transfers.
-        // most files are just photos, and 1 second will give a good headstart.
-        thread::sleep(Duration::from_millis(1000));
+    thread::scope(|scope| {
+        for _ in 0..MAX_CONCURRENT_TRANSFERS.min(files_total).max(1) {
+            scope.spawn(|| loop {
+                let entry = match work_queue.lock().unwrap().pop_front() {
+                    Some(entry) => entry,
+                    None => break,
+                };
 
-        new_transferred_files.insert(destination_file_path);
-        count_files_transferred += 1;
+                let destination_file_path = format!("{}/{}", destination_dir, entry.name);
+                let source_file_path = format!("{}/{}", source_dir, entry.name);
 
-        if count_files_transferred % 10 == 0 {
-            info!("{} files transferred", count_files_transferred);
+                if let Err(e) = pipe_transfer(source, &source_file_path, destination, &destination_file_path, &bytes_done) {
+                    error!("Failed to transfer file {} to {}: {}", source_file_path, destination_file_path, e);
+                    files_remaining.fetch_sub(1, Ordering::Relaxed);
+                    continue;
+                }
+
+                new_transferred_files.lock().unwrap().insert(destination_file_path.clone(), TransferRecord {
+                    path: destination_file_path,
+                    size: entry.metadata.size,
+                    mtime: entry.metadata.mtime,
+                });
+                files_done.fetch_add(1, Ordering::Relaxed);
+                files_remaining.fetch_sub(1, Ordering::Relaxed);
+            });
         }
-    }
 
+        // report aggregate throughput/ETA on a timer instead of a flat per-file
+        // heartbeat, so progress is visible even while a single large file
+        // (e.g. a video) is still being copied.
+        scope.spawn(|| {
+            while files_remaining.load(Ordering::Relaxed) > 0 {
+                thread::sleep(Duration::from_secs(PROGRESS_LOG_INTERVAL_SECS));
+                if files_remaining.load(Ordering::Relaxed) == 0 {
+                    break;
+                }
+                log_progress(files_done.load(Ordering::Relaxed), files_total, bytes_done.load(Ordering::Relaxed), bytes_total, started_at.elapsed());
+            }
+        });
+    });
+
+    let count_files_transferred = files_done.load(Ordering::Relaxed);
     if count_files_transferred > 0 {
-        save_transferred_files(TRANSFERRED_FILES_FILE, &new_transferred_files)?;
-        log_action(LOG_FILE, &count_files_transferred)?;
+        log_progress(count_files_transferred, files_total, bytes_done.load(Ordering::Relaxed), bytes_total, started_at.elapsed());
+        save_transferred_files(TRANSFERRED_FILES_FILE, &new_transferred_files.into_inner().unwrap())?;
+        log_action(LOG_FILE, &(count_files_transferred as i32))?;
         info!("Transferred {} new files", count_files_transferred);
         info!("Transfer complete");
     }
@@ -156,66 +354,93 @@ fn transfer_files(source_dir: &str, destination_dir: &str) -> Result<()> {
     Ok(())
 }
 
-fn device_is_connected(device_name: &str, source_dir_template: &str) -> Option<String> {
-    // find device by device_name, return full directory path to that device.
-    let output = Command::new("lsusb")
-        .output()
-        .expect("Failed to list usb devices");
-
-    let output_string = String::from_utf8_lossy(&output.stdout);
-    let output_lines: Vec<&str> = output_string.split("\n").collect();
-
-    for line in output_lines {
-        if line.contains(device_name) {
-            // line looks something like:
-            // Bus 003 Device 026: ID 04e8:6860 Samsung Electronics Co., Ltd Galaxy (MTP)
-            debug!("Device connected: {}", line);
-            let usb_bus = &line[4..7];
-            let usb_device = &line[15..18];
-            let mut source_dir = String::from(source_dir_template);
-            source_dir = str::replace(&source_dir, "__BUS__", usb_bus);
-            source_dir = str::replace(&source_dir, "__DEVICE__", usb_device);
-            if path_exists(&source_dir) {
-                debug!("MTP Connections OK: Source dir found");
-                return Some(source_dir)
-            }
-            else {
-                debug!("Device connected, but MTP connections not permitted on device");
-                break;
-            }
+fn adb_sync_reachable(storage_root: &str) -> bool {
+    // confirm the adb sync service is actually up and can see the storage
+    // root before handing it to transfer_files, since a udev add event can
+    // fire slightly before adbd has finished coming up on the device.
+    match adb::stat_file(storage_root) {
+        Ok(stat) => stat.mode != 0,
+        Err(e) => {
+            debug!("adb sync service not reachable yet: {}", e);
+            false
         }
     }
-    None
 }
 
 fn main() {
     log4rs::init_file("log4rs.yml", Default::default()).unwrap();
     info!("Service started");
     info!("Waiting for device to connect...");
+
+    let mut hotplug_monitor = usb::HotplugMonitor::new().expect("Failed to start udev hotplug monitor");
+
     loop {
-        let source_dir = match device_is_connected(DEVICE_NAME, SOURCE_DIR_TEMPLATE) {
-            None => {
-                debug!("Waiting for device to connect...");
-                thread::sleep(Duration::from_millis(WAIT_TIME_CONNECT_LOOP * 1000));
-                continue;
+        // a device may already be plugged in at startup, so check before
+        // waiting on the next hotplug event. the bus/device numbers are not
+        // needed beyond this point: adb talks to the phone over its own
+        // transport, so the udev event is used purely as a "something
+        // connected" signal, confirmed by actually reaching the sync service.
+        match usb::find_mtp_device().unwrap_or(None) {
+            Some((_usb_device, syspath)) => hotplug_monitor.set_connected_interface(&syspath),
+            None => match hotplug_monitor.next_event() {
+                Some(usb::UsbEvent::Connected(_usb_device)) => (),
+                _ => continue,
             },
-            Some(source_dir) => source_dir,
         };
 
-        match transfer_files(&source_dir, DESTINATION_DIR) {
+        // the phone can be seen on the bus slightly before adbd finishes
+        // coming up, so back off and retry rather than hammering the adb
+        // server with connection attempts while we wait.
+        if !adb_sync_reachable(SOURCE_STORAGE_ROOT) {
+            thread::sleep(Duration::from_secs(ADB_SYNC_RETRY_INTERVAL_SECS));
+            continue;
+        }
+
+        let source_backend = backend::adb::AdbBackend;
+        let destination_backend = backend::fs::FsBackend;
+
+        match transfer_files(&source_backend, &destination_backend, SOURCE_STORAGE_ROOT, DESTINATION_DIR) {
             Ok(_result) => info!("Re-connect device to begin a new transfer."),
             Err(error)  => error!("File transfer resulted in an error: {}. Re-connect device to try again.", error),
         }
 
         loop {
-            if path_exists(&source_dir) {
-                debug!("Waiting for device to disconnect...");
-                thread::sleep(Duration::from_millis(WAIT_TIME_DISCONNECT_LOOP * 1000));
-            }
-            else {
-                info!("Device disconnected");
-                break;
+            match hotplug_monitor.next_event() {
+                Some(usb::UsbEvent::Disconnected) => {
+                    info!("Device disconnected");
+                    break;
+                }
+                _ => continue,
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_record_round_trips_through_to_line_and_from_line() {
+        let record = TransferRecord {
+            path: "/home/hannu/move/files/to/path/DCIM/IMG_0001.jpg".to_string(),
+            size: 5_368_709_120, // 5 GiB; would have wrapped as a u32.
+            mtime: 4_294_967_296, // past year 2106; would have wrapped as a u32.
+        };
+        let parsed = TransferRecord::from_line(&record.to_line()).unwrap();
+        assert_eq!(parsed.path, record.path);
+        assert_eq!(parsed.size, record.size);
+        assert_eq!(parsed.mtime, record.mtime);
+    }
+
+    #[test]
+    fn transfer_record_from_line_rejects_missing_fields() {
+        assert!(TransferRecord::from_line("only/a/path").is_none());
+        assert!(TransferRecord::from_line("a/path\t123").is_none());
+    }
+
+    #[test]
+    fn transfer_record_from_line_rejects_non_numeric_fields() {
+        assert!(TransferRecord::from_line("a/path\tnot-a-number\t0").is_none());
+    }
+}
@@ -0,0 +1,143 @@
+// USB device detection and hotplug monitoring via udev, replacing the old
+// `lsusb` stdout scraping. A device is considered "the phone" if it exposes
+// a PTP/MTP interface (class/subclass/protocol 6/1/1), regardless of vendor.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use udev::{Enumerator, EventType, MonitorBuilder};
+
+const MTP_INTERFACE_CLASS: &str = "06";
+const MTP_INTERFACE_SUBCLASS: &str = "01";
+const MTP_INTERFACE_PROTOCOL: &str = "01";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbDevice {
+    pub bus: u8,
+    pub device: u8,
+}
+
+#[derive(Debug)]
+pub enum UsbEvent {
+    Connected(UsbDevice),
+    Disconnected,
+}
+
+fn is_mtp_interface(device: &udev::Device) -> bool {
+    device.devtype().map(|t| t.to_str()) == Some(Some("usb_interface"))
+        && device.attribute_value("bInterfaceClass").and_then(|v| v.to_str()) == Some(MTP_INTERFACE_CLASS)
+        && device.attribute_value("bInterfaceSubClass").and_then(|v| v.to_str()) == Some(MTP_INTERFACE_SUBCLASS)
+        && device.attribute_value("bInterfaceProtocol").and_then(|v| v.to_str()) == Some(MTP_INTERFACE_PROTOCOL)
+}
+
+fn usb_device_from_interface(interface: &udev::Device) -> Option<UsbDevice> {
+    let parent = interface.parent()?;
+    let bus: u8 = parent.attribute_value("busnum")?.to_str()?.parse().ok()?;
+    let device: u8 = parent.attribute_value("devnum")?.to_str()?.parse().ok()?;
+    Some(UsbDevice { bus, device })
+}
+
+// walk all usb interfaces currently known to udev and return the first one
+// that looks like a PTP/MTP device, along with its syspath (so the caller can
+// hand it to `HotplugMonitor::set_connected_interface` and have a later
+// Remove event for it recognized as a disconnect).
+pub fn find_mtp_device() -> io::Result<Option<(UsbDevice, PathBuf)>> {
+    let mut enumerator = Enumerator::new()?;
+    enumerator.match_subsystem("usb")?;
+
+    for interface in enumerator.scan_devices()? {
+        if is_mtp_interface(&interface) {
+            if let Some(usb_device) = usb_device_from_interface(&interface) {
+                debug!("Found MTP interface on bus {} device {}", usb_device.bus, usb_device.device);
+                return Ok(Some((usb_device, interface.syspath().to_path_buf())));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// a netlink monitor that blocks on USB add/remove events, so the outer loop
+// in `main` can react to hotplug instead of polling every few seconds.
+pub struct HotplugMonitor {
+    socket: udev::MonitorSocket,
+    // syspath of the MTP interface we last reported Connected for, so a
+    // later Remove event for that same interface can be recognized (see
+    // next_event's Remove arm for why this can't be done by re-checking
+    // interface class/subclass/protocol, as Add does).
+    connected_interface: Option<PathBuf>,
+}
+
+impl HotplugMonitor {
+    pub fn new() -> io::Result<HotplugMonitor> {
+        let socket = MonitorBuilder::new()?.match_subsystem("usb")?.listen()?;
+        Ok(HotplugMonitor { socket, connected_interface: None })
+    }
+
+    // record `syspath` as the MTP interface currently connected, so a later
+    // Remove event for it is recognized as UsbEvent::Disconnected. Needed
+    // when the caller learns about the device from `find_mtp_device`'s
+    // startup scan rather than from this monitor's own Add event.
+    pub fn set_connected_interface(&mut self, syspath: &Path) {
+        self.connected_interface = Some(syspath.to_path_buf());
+    }
+
+    // block until the next relevant USB interface event and return what changed.
+    // non-MTP interfaces and events we don't care about (bind/unbind) are
+    // skipped transparently.
+    //
+    // `MonitorSocket::iter()` only drains events already queued on the netlink
+    // socket and returns immediately when there are none, so it is polled on
+    // the socket's raw fd first -- otherwise callers that loop on `next_event`
+    // would busy-spin instead of actually waiting for the next event.
+    pub fn next_event(&mut self) -> Option<UsbEvent> {
+        loop {
+            for event in self.socket.iter() {
+                match event.event_type() {
+                    EventType::Add => {
+                        let device = event.device();
+                        if is_mtp_interface(&device) {
+                            if let Some(usb_device) = usb_device_from_interface(&device) {
+                                self.connected_interface = Some(device.syspath().to_path_buf());
+                                return Some(UsbEvent::Connected(usb_device));
+                            }
+                        }
+                    }
+                    EventType::Remove => {
+                        // by the time a Remove event is delivered the interface's
+                        // sysfs node is already gone, so attribute reads like
+                        // is_mtp_interface uses (bInterfaceClass and friends)
+                        // always come back empty here. Match on the syspath
+                        // recorded when we saw this interface's Add instead.
+                        let device = event.device();
+                        if self.connected_interface.as_deref() == Some(device.syspath()) {
+                            self.connected_interface = None;
+                            return Some(UsbEvent::Disconnected);
+                        }
+                    }
+                    _ => continue,
+                }
+            }
+
+            if let Err(e) = self.wait_for_event() {
+                debug!("error polling udev monitor socket: {}", e);
+                return None;
+            }
+        }
+    }
+
+    // block until the monitor socket is readable, i.e. at least one event is
+    // ready to be drained by `iter()`.
+    fn wait_for_event(&self) -> io::Result<()> {
+        let mut pollfd = libc::pollfd {
+            fd: self.socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let result = unsafe { libc::poll(&mut pollfd, 1, -1) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}